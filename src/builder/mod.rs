@@ -2,6 +2,8 @@
 //! serves as an input to a program's endpoints.
 
 use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar;
 use anchor_lang::{prelude::*, system_program};
 use solana_sdk::program_pack::Pack;
 use std::cell::RefCell;
@@ -205,6 +207,19 @@ impl AccountInfoWrapper {
         self
     }
 
+    /// Builds the `sysvar::instructions` account so that a tested program can
+    /// introspect sibling instructions via `load_current_index_checked` /
+    /// `load_instruction_at_checked`, e.g. to assert it's being CPI'd by a
+    /// trusted caller.
+    pub fn instructions_sysvar(
+        ixs: &[Instruction],
+        current_index: u16,
+    ) -> Self {
+        Self::with_key(sysvar::instructions::ID)
+            .owner(sysvar::ID)
+            .raw(serialize_instructions_sysvar(ixs, current_index))
+    }
+
     /// # Note
     /// Be careful to check that the implementation of [`AccountSerialize`] is
     /// not a no-op. For some types, anchor skips serialization because it
@@ -235,3 +250,84 @@ impl AccountInfoWrapper {
         self
     }
 }
+
+/// Serializes instructions into the exact on-chain layout used by the
+/// `sysvar::instructions` account: a `num_instructions: u16` header, followed
+/// by a table of `u16` offsets (one per instruction) pointing at each
+/// instruction's blob, followed by the blobs themselves, followed by a
+/// trailing `current_instruction_index: u16`.
+fn serialize_instructions_sysvar(
+    ixs: &[Instruction],
+    current_index: u16,
+) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&(ixs.len() as u16).to_le_bytes());
+
+    let offsets_start = data.len();
+    data.resize(offsets_start + ixs.len() * 2, 0);
+
+    for (i, ix) in ixs.iter().enumerate() {
+        let offset = data.len() as u16;
+        data[offsets_start + i * 2..offsets_start + i * 2 + 2]
+            .copy_from_slice(&offset.to_le_bytes());
+
+        data.extend_from_slice(&(ix.accounts.len() as u16).to_le_bytes());
+        for meta in &ix.accounts {
+            let mut flags = 0u8;
+            if meta.is_signer {
+                flags |= 0b01;
+            }
+            if meta.is_writable {
+                flags |= 0b10;
+            }
+            data.push(flags);
+            data.extend_from_slice(meta.pubkey.as_ref());
+        }
+        data.extend_from_slice(ix.program_id.as_ref());
+        data.extend_from_slice(&(ix.data.len() as u16).to_le_bytes());
+        data.extend_from_slice(&ix.data);
+    }
+
+    data.extend_from_slice(&current_index.to_le_bytes());
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::instruction::AccountMeta;
+    use anchor_lang::solana_program::sysvar::instructions::{
+        load_current_index_checked, load_instruction_at_checked,
+    };
+
+    #[test]
+    fn it_builds_instructions_sysvar() {
+        let ix0 = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), true),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            ],
+            data: vec![1, 2, 3],
+        };
+        let ix1 = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new_readonly(
+                Pubkey::new_unique(),
+                false,
+            )],
+            data: vec![4, 5],
+        };
+
+        let mut wrapper = AccountInfoWrapper::instructions_sysvar(
+            &[ix0.clone(), ix1.clone()],
+            1,
+        );
+        let info = wrapper.to_account_info();
+
+        assert_eq!(load_current_index_checked(&info).unwrap(), 1);
+        assert_eq!(load_instruction_at_checked(0, &info).unwrap(), ix0);
+        assert_eq!(load_instruction_at_checked(1, &info).unwrap(), ix1);
+    }
+}
@@ -146,3 +146,297 @@ pub mod token_account {
         Some(())
     }
 }
+
+pub mod cpi {
+    //! An opt-in [`ValidateCpis`] implementation which applies the effects of
+    //! CPIs into the SPL-token program to the passed account infos, so that
+    //! programs which transfer/mint/burn tokens via CPI can be tested against
+    //! real post-CPI balances.
+
+    use super::{mint, token_account};
+    use crate::stub::ValidateCpis;
+    use anchor_lang::prelude::*;
+    use anchor_lang::solana_program::instruction::Instruction;
+    use anchor_spl::token::spl_token;
+    use anchor_spl::token::spl_token::instruction::TokenInstruction;
+
+    /// Wraps another [`ValidateCpis`] implementation and applies the effect
+    /// of any CPI into the SPL-token program (transfer, mint, burn, and their
+    /// `*Checked` variants) to the relevant accounts, after first delegating
+    /// to the wrapped validator.
+    ///
+    /// This lets a program's own CPI assertions (e.g. a state machine
+    /// checking the CPIs happen in the right order) compose with real
+    /// balance changes, instead of having to fake them in the test.
+    pub struct SplTokenCpiExecutor<V> {
+        pub inner: V,
+    }
+
+    impl<V> SplTokenCpiExecutor<V> {
+        pub fn new(inner: V) -> Self {
+            Self { inner }
+        }
+    }
+
+    impl<V: ValidateCpis> ValidateCpis for SplTokenCpiExecutor<V> {
+        fn validate_next_instruction_with_signers(
+            &mut self,
+            ix: &Instruction,
+            accounts: &[AccountInfo],
+            signers: &[Pubkey],
+        ) {
+            self.inner
+                .validate_next_instruction_with_signers(ix, accounts, signers);
+
+            if ix.program_id != spl_token::ID {
+                return;
+            }
+
+            apply_token_instruction(ix, accounts)
+                .expect("Cannot apply SPL-token CPI effect");
+        }
+    }
+
+    /// Looks up the account passed in the `meta_index`-th [`AccountMeta`] of
+    /// `ix` among `accounts`.
+    fn account<'a, 'info>(
+        ix: &Instruction,
+        accounts: &'a [AccountInfo<'info>],
+        meta_index: usize,
+    ) -> Option<&'a AccountInfo<'info>> {
+        let pubkey = ix.accounts.get(meta_index)?.pubkey;
+        accounts.iter().find(|a| *a.key == pubkey)
+    }
+
+    fn apply_token_instruction(
+        ix: &Instruction,
+        accounts: &[AccountInfo],
+    ) -> Option<()> {
+        let account = |meta_index: usize| account(ix, accounts, meta_index);
+
+        match TokenInstruction::unpack(&ix.data).ok()? {
+            TokenInstruction::Transfer { amount } => {
+                token_account::transfer(account(0)?, account(1)?, amount)
+            }
+            TokenInstruction::TransferChecked { amount, .. } => {
+                token_account::transfer(account(0)?, account(2)?, amount)
+            }
+            TokenInstruction::MintTo { amount }
+            | TokenInstruction::MintToChecked { amount, .. } => {
+                mint::mint_to(account(1)?, account(0)?, amount)
+            }
+            TokenInstruction::Burn { amount }
+            | TokenInstruction::BurnChecked { amount, .. } => {
+                mint::burn_from(account(0)?, account(1)?, amount)
+            }
+            _ => Some(()),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::builder::AccountInfoWrapper;
+        use crate::spl::{mint, token_account, MintExt, TokenAccountExt};
+        use crate::stub::Syscalls;
+        use anchor_lang::solana_program::program::invoke;
+
+        struct NoopValidator;
+        impl ValidateCpis for NoopValidator {
+            fn validate_next_instruction_with_signers(
+                &mut self,
+                _ix: &Instruction,
+                _accounts: &[AccountInfo],
+                _signers: &[Pubkey],
+            ) {
+            }
+        }
+
+        fn set_syscalls() {
+            Syscalls::new(SplTokenCpiExecutor::new(NoopValidator)).set();
+        }
+
+        #[test]
+        fn it_applies_transfer() {
+            set_syscalls();
+
+            let mint_key = Pubkey::new_unique();
+            let authority = Pubkey::new_unique();
+
+            let mut from = AccountInfoWrapper::with_key(Pubkey::new_unique())
+                .owner(spl_token::ID)
+                .pack(token_account::new(authority).mint(mint_key).amount(100));
+            let mut into = AccountInfoWrapper::with_key(Pubkey::new_unique())
+                .owner(spl_token::ID)
+                .pack(token_account::new(authority).mint(mint_key).amount(0));
+            let mut authority_acc =
+                AccountInfoWrapper::with_key(authority).signer();
+
+            let from_info = from.to_account_info();
+            let into_info = into.to_account_info();
+            let authority_info = authority_acc.to_account_info();
+
+            let ix = spl_token::instruction::transfer(
+                &spl_token::ID,
+                from_info.key,
+                into_info.key,
+                &authority,
+                &[],
+                40,
+            )
+            .unwrap();
+
+            invoke(
+                &ix,
+                &[
+                    from_info.clone(),
+                    into_info.clone(),
+                    authority_info.clone(),
+                ],
+            )
+            .unwrap();
+
+            assert_eq!(token_account::from_acc_info(&from_info).amount, 60);
+            assert_eq!(token_account::from_acc_info(&into_info).amount, 40);
+        }
+
+        #[test]
+        fn it_applies_transfer_checked() {
+            set_syscalls();
+
+            let mint_key = Pubkey::new_unique();
+            let authority = Pubkey::new_unique();
+
+            let mut from = AccountInfoWrapper::with_key(Pubkey::new_unique())
+                .owner(spl_token::ID)
+                .pack(token_account::new(authority).mint(mint_key).amount(100));
+            let mut token_mint = AccountInfoWrapper::with_key(mint_key)
+                .owner(spl_token::ID)
+                .pack(mint::new(authority).supply(100));
+            let mut into = AccountInfoWrapper::with_key(Pubkey::new_unique())
+                .owner(spl_token::ID)
+                .pack(token_account::new(authority).mint(mint_key).amount(0));
+            let mut authority_acc =
+                AccountInfoWrapper::with_key(authority).signer();
+
+            let from_info = from.to_account_info();
+            let mint_info = token_mint.to_account_info();
+            let into_info = into.to_account_info();
+            let authority_info = authority_acc.to_account_info();
+
+            let ix = spl_token::instruction::transfer_checked(
+                &spl_token::ID,
+                from_info.key,
+                mint_info.key,
+                into_info.key,
+                &authority,
+                &[],
+                40,
+                0,
+            )
+            .unwrap();
+
+            invoke(
+                &ix,
+                &[
+                    from_info.clone(),
+                    mint_info.clone(),
+                    into_info.clone(),
+                    authority_info.clone(),
+                ],
+            )
+            .unwrap();
+
+            assert_eq!(token_account::from_acc_info(&from_info).amount, 60);
+            assert_eq!(token_account::from_acc_info(&into_info).amount, 40);
+        }
+
+        #[test]
+        fn it_applies_mint_to() {
+            set_syscalls();
+
+            let authority = Pubkey::new_unique();
+            let mint_key = Pubkey::new_unique();
+
+            let mut token_mint = AccountInfoWrapper::with_key(mint_key)
+                .owner(spl_token::ID)
+                .pack(mint::new(authority).supply(100));
+            let mut wallet = AccountInfoWrapper::with_key(Pubkey::new_unique())
+                .owner(spl_token::ID)
+                .pack(token_account::new(authority).mint(mint_key).amount(0));
+            let mut authority_acc =
+                AccountInfoWrapper::with_key(authority).signer();
+
+            let mint_info = token_mint.to_account_info();
+            let wallet_info = wallet.to_account_info();
+            let authority_info = authority_acc.to_account_info();
+
+            let ix = spl_token::instruction::mint_to(
+                &spl_token::ID,
+                mint_info.key,
+                wallet_info.key,
+                &authority,
+                &[],
+                25,
+            )
+            .unwrap();
+
+            invoke(
+                &ix,
+                &[
+                    mint_info.clone(),
+                    wallet_info.clone(),
+                    authority_info.clone(),
+                ],
+            )
+            .unwrap();
+
+            assert_eq!(mint::from_acc_info(&mint_info).supply, 125);
+            assert_eq!(token_account::from_acc_info(&wallet_info).amount, 25);
+        }
+
+        #[test]
+        fn it_applies_burn() {
+            set_syscalls();
+
+            let authority = Pubkey::new_unique();
+            let mint_key = Pubkey::new_unique();
+
+            let mut wallet = AccountInfoWrapper::with_key(Pubkey::new_unique())
+                .owner(spl_token::ID)
+                .pack(token_account::new(authority).mint(mint_key).amount(50));
+            let mut token_mint = AccountInfoWrapper::with_key(mint_key)
+                .owner(spl_token::ID)
+                .pack(mint::new(authority).supply(100));
+            let mut authority_acc =
+                AccountInfoWrapper::with_key(authority).signer();
+
+            let wallet_info = wallet.to_account_info();
+            let mint_info = token_mint.to_account_info();
+            let authority_info = authority_acc.to_account_info();
+
+            let ix = spl_token::instruction::burn(
+                &spl_token::ID,
+                wallet_info.key,
+                mint_info.key,
+                &authority,
+                &[],
+                20,
+            )
+            .unwrap();
+
+            invoke(
+                &ix,
+                &[
+                    wallet_info.clone(),
+                    mint_info.clone(),
+                    authority_info.clone(),
+                ],
+            )
+            .unwrap();
+
+            assert_eq!(token_account::from_acc_info(&wallet_info).amount, 30);
+            assert_eq!(mint::from_acc_info(&mint_info).supply, 80);
+        }
+    }
+}
@@ -40,17 +40,71 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::entrypoint::ProgramResult;
 use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar::fees::Fees;
 use std::sync::{Arc, Mutex};
 use std::{mem, slice};
 
+/// Copies the byte representation of `value` into `var_addr`. This is how
+/// the real runtime populates the destination buffer for `sol_get_*_sysvar`
+/// syscalls.
+///
+/// # Safety
+/// `var_addr` must point to a buffer at least `mem::size_of::<S>()` bytes
+/// long, as guaranteed by the BPF syscall ABI.
+unsafe fn copy_sysvar<S>(var_addr: *mut u8, value: &S) {
+    let size = mem::size_of::<S>();
+    let var = slice::from_raw_parts_mut(var_addr, size);
+    let bytes =
+        slice::from_raw_parts((value as *const S) as *const u8, size);
+    var.copy_from_slice(bytes);
+}
+
+/// Flat compute-unit cost charged for every `sol_invoke_signed`, mirroring
+/// the real runtime's fixed per-CPI overhead.
+const CPI_COMPUTE_UNITS: u64 = 1_000;
+
+/// The default per-instruction compute budget, matching the runtime's
+/// `ThisComputeMeter` default.
+const DEFAULT_COMPUTE_BUDGET: u64 = 200_000;
+
 pub trait ValidateCpis {
+    /// Called for every CPI, together with the pubkeys of any PDA signers
+    /// the program authenticated the CPI with via `signers_seeds`.
+    ///
+    /// The default implementation ignores `signers` and delegates to
+    /// [`Self::validate_next_instruction`], so existing implementors who
+    /// don't care about PDA signers don't need to change anything.
+    fn validate_next_instruction_with_signers(
+        &mut self,
+        ix: &Instruction,
+        accounts: &[AccountInfo],
+        signers: &[Pubkey],
+    ) {
+        let _ = signers;
+        self.validate_next_instruction(ix, accounts);
+    }
+
     /// Every time the program triggers a CPI, this method is called with the
-    /// payload.
+    /// payload. Superseded by
+    /// [`Self::validate_next_instruction_with_signers`] for validators which
+    /// need to assert that a CPI's signer is backed by a PDA the program
+    /// legitimately controls.
+    ///
+    /// The default panics: a validator must override this method, or
+    /// [`Self::validate_next_instruction_with_signers`], to have any effect.
+    /// Without this, a validator that forgets to override either method
+    /// would silently validate nothing.
     fn validate_next_instruction(
         &mut self,
         ix: &Instruction,
-        accounts: &[AccountInfo],
-    );
+        _accounts: &[AccountInfo],
+    ) {
+        panic!(
+            "validate_next_instruction(_with_signers) not implemented, \
+             got CPI into {}",
+            ix.program_id
+        );
+    }
 }
 
 /// Holds the necessary state which determines the configurable behavior of
@@ -61,6 +115,13 @@ pub trait ValidateCpis {
 pub struct Syscalls<T> {
     cpi_validator: Arc<Mutex<T>>,
     clock: Arc<Mutex<Clock>>,
+    rent: Arc<Mutex<Rent>>,
+    epoch_schedule: Arc<Mutex<EpochSchedule>>,
+    fees: Arc<Mutex<Fees>>,
+    remaining_compute_units: Arc<Mutex<u64>>,
+    /// The tested program's id, used to resolve `signers_seeds` passed to
+    /// `sol_invoke_signed` into the PDA pubkeys they sign for.
+    program_id: Arc<Mutex<Pubkey>>,
     // All captured solana logs are pushed into this vector in order
     logs: Arc<Mutex<Vec<String>>>,
 }
@@ -71,6 +132,13 @@ impl<T: ValidateCpis + Send + Sync + 'static> Syscalls<T> {
             cpi_validator: Arc::new(Mutex::new(cpi_validator)),
             logs: Default::default(),
             clock: Default::default(),
+            rent: Default::default(),
+            epoch_schedule: Default::default(),
+            fees: Default::default(),
+            remaining_compute_units: Arc::new(Mutex::new(
+                DEFAULT_COMPUTE_BUDGET,
+            )),
+            program_id: Default::default(),
         }
     }
 
@@ -96,6 +164,79 @@ impl<T: ValidateCpis + Send + Sync + 'static> Syscalls<T> {
         *guard = clock;
     }
 
+    /// Overwrites the rent object, e.g. to control the rent-exemption
+    /// minimums a tested program computes with `Rent::get()`.
+    ///
+    /// This method has no effect without calling [`Syscalls::set`]
+    pub fn rent(&self, rent: Rent) {
+        let mut guard = self.rent.lock().unwrap();
+        *guard = rent;
+    }
+
+    /// Overwrites the epoch schedule object.
+    ///
+    /// This method has no effect without calling [`Syscalls::set`]
+    pub fn epoch_schedule(&self, epoch_schedule: EpochSchedule) {
+        let mut guard = self.epoch_schedule.lock().unwrap();
+        *guard = epoch_schedule;
+    }
+
+    /// Sets the lamports-per-signature fee returned by the fees sysvar.
+    ///
+    /// This method has no effect without calling [`Syscalls::set`]
+    pub fn fees(&self, lamports_per_signature: u64) {
+        let mut guard = self.fees.lock().unwrap();
+        guard.fee_calculator.lamports_per_signature = lamports_per_signature;
+    }
+
+    /// Sets the remaining compute-unit budget. [`Syscalls::new`] starts with
+    /// `200_000`, mirroring the runtime's default per-instruction compute
+    /// budget; [`Default::default`] starts with `0`.
+    ///
+    /// The new budget is seen by [`Syscalls::consume`] and
+    /// [`Syscalls::remaining_compute_units`] right away; CPIs made by the
+    /// tested program only consume it after calling [`Syscalls::set`].
+    pub fn compute_budget(&self, units: u64) {
+        let mut guard = self.remaining_compute_units.lock().unwrap();
+        *guard = units;
+    }
+
+    /// Returns the remaining compute units.
+    pub fn remaining_compute_units(&self) -> u64 {
+        *self.remaining_compute_units.lock().unwrap()
+    }
+
+    /// Deducts `amount` from the remaining compute-unit budget. If the
+    /// budget would go negative, pushes an error log and clamps the
+    /// remainder at zero, so a test can observe the program blowing its
+    /// budget (via [`Syscalls::logs`]) instead of it silently succeeding.
+    pub fn consume(&self, amount: u64) {
+        let mut guard = self.remaining_compute_units.lock().unwrap();
+        match guard.checked_sub(amount) {
+            Some(remaining) => *guard = remaining,
+            None => {
+                *guard = 0;
+                drop(guard);
+                self.logs.lock().unwrap().push(
+                    "Program failed to complete: exceeded CUs meter \
+                     at BPF instruction"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    /// Sets the tested program's id. Used to resolve the `signers_seeds`
+    /// passed to `invoke_signed` into the PDA pubkeys a CPI was signed with,
+    /// so a [`ValidateCpis`] can assert the program legitimately controls
+    /// them.
+    ///
+    /// This method has no effect without calling [`Syscalls::set`]
+    pub fn program_id(&self, program_id: Pubkey) {
+        let mut guard = self.program_id.lock().unwrap();
+        *guard = program_id;
+    }
+
     pub fn validator(&self) -> Arc<Mutex<T>> {
         Arc::clone(&self.cpi_validator)
     }
@@ -114,46 +255,137 @@ impl<T: ValidateCpis + Send + Sync> solana_sdk::program_stubs::SyscallStubs
     }
 
     fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
-        let size_of_clock = mem::size_of::<Clock>();
-        let clock = &*self.clock.lock().unwrap();
-        unsafe {
-            let var = slice::from_raw_parts_mut(var_addr, size_of_clock);
-            let clock_bytes = slice::from_raw_parts(
-                (clock as *const Clock) as *const u8,
-                size_of_clock,
-            );
-            var.copy_from_slice(clock_bytes);
-        }
-
+        unsafe { copy_sysvar(var_addr, &*self.clock.lock().unwrap()) };
         0
     }
 
-    fn sol_get_epoch_schedule_sysvar(&self, _var_addr: *mut u8) -> u64 {
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { copy_sysvar(var_addr, &*self.epoch_schedule.lock().unwrap()) };
         0
     }
 
-    fn sol_get_fees_sysvar(&self, _var_addr: *mut u8) -> u64 {
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { copy_sysvar(var_addr, &*self.fees.lock().unwrap()) };
         0
     }
 
-    fn sol_get_rent_sysvar(&self, _var_addr: *mut u8) -> u64 {
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { copy_sysvar(var_addr, &*self.rent.lock().unwrap()) };
         0
     }
 
+    fn sol_log_compute_units(&self) {
+        self.sol_log(&format!(
+            "Program consumption: {} units remaining",
+            self.remaining_compute_units()
+        ));
+    }
+
     fn sol_invoke_signed(
         &self,
         instruction: &Instruction,
         account_infos: &[AccountInfo<'_>],
-        _signers_seeds: &[&[&[u8]]],
+        signers_seeds: &[&[&[u8]]],
     ) -> ProgramResult {
+        self.consume(CPI_COMPUTE_UNITS);
+
+        let program_id = *self.program_id.lock().unwrap();
+        let signers: Vec<Pubkey> = signers_seeds
+            .iter()
+            .filter_map(|seeds| {
+                match Pubkey::create_program_address(seeds, &program_id) {
+                    Ok(pubkey) => Some(pubkey),
+                    Err(err) => {
+                        self.sol_log(&format!(
+                            "Could not derive PDA from seeds {:?} and \
+                             program id {}: {}",
+                            seeds, program_id, err
+                        ));
+                        None
+                    }
+                }
+            })
+            .collect();
+
         let mut cpis = self.cpi_validator.lock().expect("Cannot obtain lock");
 
-        cpis.validate_next_instruction(instruction, account_infos);
+        cpis.validate_next_instruction_with_signers(
+            instruction,
+            account_infos,
+            &signers,
+        );
 
         Ok(())
     }
 }
 
+/// A snapshot of an [`AccountInfo`]'s relevant fields, taken at the
+/// time a CPI was recorded by [`RecordingValidator`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedAccount {
+    pub key: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+/// A CPI recorded by [`RecordingValidator`]: the instruction together with a
+/// snapshot of the relevant accounts' keys and metas, and the PDA pubkeys (if
+/// any) the CPI was signed with.
+#[derive(Clone, Debug)]
+pub struct RecordedCpi {
+    pub instruction: Instruction,
+    pub accounts: Vec<RecordedAccount>,
+    pub signers: Vec<Pubkey>,
+}
+
+/// A [`ValidateCpis`] which records every CPI instead of validating it,
+/// modeled on the runtime's `InstructionRecorder`. Useful when a test just
+/// wants to assert which CPIs happened, and in what order, without writing a
+/// state machine for every test.
+///
+/// Access the recorded CPIs via [`Syscalls::recorded_cpis`].
+#[derive(Default, Clone, Debug)]
+pub struct RecordingValidator {
+    recorded: Arc<Mutex<Vec<RecordedCpi>>>,
+}
+
+impl RecordingValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ValidateCpis for RecordingValidator {
+    fn validate_next_instruction_with_signers(
+        &mut self,
+        ix: &Instruction,
+        accounts: &[AccountInfo],
+        signers: &[Pubkey],
+    ) {
+        self.recorded.lock().unwrap().push(RecordedCpi {
+            instruction: ix.clone(),
+            accounts: accounts
+                .iter()
+                .map(|a| RecordedAccount {
+                    key: *a.key,
+                    is_signer: a.is_signer,
+                    is_writable: a.is_writable,
+                })
+                .collect(),
+            signers: signers.to_vec(),
+        });
+    }
+}
+
+impl Syscalls<RecordingValidator> {
+    /// Returns all CPIs recorded so far, in the order they happened.
+    pub fn recorded_cpis(&self) -> Vec<RecordedCpi> {
+        let validator = self.validator();
+        let validator = validator.lock().unwrap();
+        validator.recorded.lock().unwrap().clone()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -198,4 +430,125 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn it_sets_rent_epoch_schedule_and_fees() {
+        let syscalls = Syscalls::new(StubValidator);
+        syscalls.rent(Rent {
+            lamports_per_byte_year: 10,
+            ..Default::default()
+        });
+        syscalls.epoch_schedule(EpochSchedule {
+            slots_per_epoch: 20,
+            ..Default::default()
+        });
+        syscalls.fees(5);
+        syscalls.set();
+
+        assert_eq!(Rent::get().unwrap().lamports_per_byte_year, 10);
+        assert_eq!(EpochSchedule::get().unwrap().slots_per_epoch, 20);
+        assert_eq!(
+            Fees::get().unwrap().fee_calculator.lamports_per_signature,
+            5
+        );
+    }
+
+    #[test]
+    fn it_meters_compute_units() {
+        let syscalls = Syscalls::new(StubValidator);
+        syscalls.compute_budget(100);
+        assert_eq!(syscalls.remaining_compute_units(), 100);
+
+        syscalls.consume(40);
+        assert_eq!(syscalls.remaining_compute_units(), 60);
+        assert!(syscalls.logs().is_empty());
+
+        syscalls.consume(1_000);
+        assert_eq!(syscalls.remaining_compute_units(), 0);
+        assert!(syscalls
+            .logs()
+            .iter()
+            .any(|log| log.contains("exceeded CUs meter")));
+    }
+
+    #[test]
+    fn it_records_cpis() {
+        let syscalls = Syscalls::new(RecordingValidator::new());
+        syscalls.program_id(Pubkey::new_unique());
+        syscalls.set();
+
+        let ix = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![1, 2, 3],
+        };
+        anchor_lang::solana_program::program::invoke_signed(&ix, &[], &[])
+            .unwrap();
+
+        let recorded = syscalls.recorded_cpis();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].instruction, ix);
+    }
+
+    #[test]
+    fn it_resolves_pda_signer_seeds() {
+        let program_id = Pubkey::new_unique();
+        let syscalls = Syscalls::new(RecordingValidator::new());
+        syscalls.program_id(program_id);
+        syscalls.set();
+
+        let seed = b"vault";
+        let (pda, bump) = Pubkey::find_program_address(&[seed], &program_id);
+        let bump_seed = [bump];
+        let seeds: &[&[u8]] = &[seed, &bump_seed];
+
+        let ix = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+        anchor_lang::solana_program::program::invoke_signed(
+            &ix,
+            &[],
+            &[seeds],
+        )
+        .unwrap();
+
+        let recorded = syscalls.recorded_cpis();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].signers, vec![pda]);
+    }
+
+    #[test]
+    fn it_logs_failed_pda_derivation() {
+        let program_id = Pubkey::new_unique();
+        let syscalls = Syscalls::new(RecordingValidator::new());
+        syscalls.program_id(program_id);
+        syscalls.set();
+
+        // Longer than `Pubkey::MAX_SEED_LEN`, so derivation deterministically
+        // fails regardless of the (randomized) program id.
+        let too_long_seed = [0u8; 33];
+        let seeds: &[&[u8]] = &[&too_long_seed];
+
+        let ix = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+        anchor_lang::solana_program::program::invoke_signed(
+            &ix,
+            &[],
+            &[seeds],
+        )
+        .unwrap();
+
+        let recorded = syscalls.recorded_cpis();
+        assert_eq!(recorded.len(), 1);
+        assert!(recorded[0].signers.is_empty());
+        assert!(syscalls
+            .logs()
+            .iter()
+            .any(|log| log.contains("Could not derive PDA")));
+    }
 }